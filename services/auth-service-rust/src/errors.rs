@@ -0,0 +1,86 @@
+// Centralized error type for the auth service. Handlers reject with an
+// `AuthError` instead of fabricating a `success: false` body, and `recover`
+// is the single place that turns a rejection into the right status code
+// and a consistent `{ "status", "message" }` envelope.
+
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::reject::Reject;
+use warp::Rejection;
+
+use crate::guards::Forbidden;
+
+#[derive(Debug)]
+pub(crate) enum AuthError {
+    MissingCredentials,
+    InvalidCredentials,
+    UserNotFound,
+    BlockedUser,
+    InvalidToken,
+    ExpiredToken,
+    InvalidRefreshToken,
+    TokenCreationError,
+    EmailTaken,
+    Internal(String),
+}
+
+impl AuthError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AuthError::MissingCredentials => StatusCode::BAD_REQUEST,
+            AuthError::InvalidCredentials
+            | AuthError::UserNotFound
+            | AuthError::InvalidToken
+            | AuthError::ExpiredToken
+            | AuthError::InvalidRefreshToken => StatusCode::UNAUTHORIZED,
+            AuthError::BlockedUser => StatusCode::FORBIDDEN,
+            AuthError::EmailTaken => StatusCode::CONFLICT,
+            AuthError::TokenCreationError | AuthError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            AuthError::MissingCredentials => "Email and password are required".to_string(),
+            AuthError::InvalidCredentials => "Invalid credentials".to_string(),
+            AuthError::UserNotFound => "User not found".to_string(),
+            AuthError::BlockedUser => "This account has been blocked".to_string(),
+            AuthError::InvalidToken => "Invalid token".to_string(),
+            AuthError::ExpiredToken => "Token has expired".to_string(),
+            AuthError::InvalidRefreshToken => "Invalid or expired refresh token".to_string(),
+            AuthError::TokenCreationError => "Failed to create token".to_string(),
+            AuthError::EmailTaken => "An account with this email already exists".to_string(),
+            AuthError::Internal(message) => message.clone(),
+        }
+    }
+}
+
+impl Reject for AuthError {}
+
+#[derive(Debug, Serialize)]
+struct ErrorBody {
+    status: u16,
+    message: String,
+}
+
+pub(crate) async fn recover(err: Rejection) -> Result<impl warp::Reply, Rejection> {
+    let (status, message) = if let Some(auth_err) = err.find::<AuthError>() {
+        (auth_err.status(), auth_err.message())
+    } else if err.find::<Forbidden>().is_some() {
+        (StatusCode::FORBIDDEN, "Forbidden".to_string())
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, e.to_string())
+    } else if err.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found".to_string())
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
+    };
+
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorBody {
+            status: status.as_u16(),
+            message,
+        }),
+        status,
+    ))
+}
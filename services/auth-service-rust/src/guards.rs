@@ -0,0 +1,41 @@
+// Composable warp filters that stand in for a request guard: resolve the
+// caller into `Claims` and short-circuit unauthorized requests before the
+// handler ever runs, instead of every handler checking a header by hand.
+// Rejections from these filters are turned into responses by `errors::recover`.
+
+use warp::{Filter, Rejection};
+
+use crate::errors::AuthError;
+use crate::{classify_token_error, verify_jwt_token, Claims};
+
+#[derive(Debug)]
+pub(crate) struct Forbidden;
+impl warp::reject::Reject for Forbidden {}
+
+/// Extracts and verifies the `Authorization: Bearer <token>` header into
+/// `Claims`, rejecting with `AuthError::ExpiredToken` for an expired token
+/// and `AuthError::InvalidToken` otherwise (including when the header is
+/// absent, so a missing header is a 401 rather than falling through to
+/// warp's built-in `MissingHeader` rejection and a 500).
+pub(crate) fn require_auth() -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization").and_then(|header: Option<String>| async move {
+        let token = header
+            .as_deref()
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| warp::reject::custom(AuthError::InvalidToken))?;
+        verify_jwt_token(token).map_err(|e| warp::reject::custom(classify_token_error(&e)))
+    })
+}
+
+/// Builds on [`require_auth`], additionally rejecting with `Forbidden` when
+/// `claims.role` doesn't match `role`.
+pub(crate) fn require_role(role: &'static str) -> impl Filter<Extract = (Claims,), Error = Rejection> + Clone {
+    require_auth().and_then(move |claims: Claims| async move {
+        if claims.role == role {
+            Ok(claims)
+        } else {
+            Err(warp::reject::custom(Forbidden))
+        }
+    })
+}
@@ -4,17 +4,33 @@ use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, D
 use bcrypt::{hash, verify, DEFAULT_COST};
 use uuid::Uuid;
 use chrono::{Utc, Duration};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use std::convert::Infallible;
 use std::env;
 
+mod errors;
+mod guards;
+mod oidc;
+mod store;
+
+use errors::AuthError;
+use store::{SharedUserStore, UserStore};
+
+/// Access tokens are short-lived; the opaque refresh token is what actually
+/// lives for a while and is the thing we can revoke.
+const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
+pub(crate) struct Claims {
     sub: String,
     exp: usize,
     iat: usize,
-    user_id: String,
-    email: String,
-    role: String,
+    pub(crate) user_id: String,
+    pub(crate) email: String,
+    pub(crate) role: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -31,21 +47,32 @@ struct RegisterRequest {
     last_name: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogoutRequest {
+    refresh_token: String,
+}
+
 #[derive(Debug, Serialize)]
-struct AuthResponse {
-    success: bool,
-    message: String,
-    token: Option<String>,
-    user: Option<UserInfo>,
+pub(crate) struct AuthResponse {
+    pub(crate) success: bool,
+    pub(crate) message: String,
+    pub(crate) token: Option<String>,
+    pub(crate) refresh_token: Option<String>,
+    pub(crate) user: Option<UserInfo>,
 }
 
 #[derive(Debug, Serialize)]
-struct UserInfo {
-    id: String,
-    email: String,
-    first_name: String,
-    last_name: String,
-    role: String,
+pub(crate) struct UserInfo {
+    pub(crate) id: String,
+    pub(crate) email: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) role: String,
 }
 
 #[tokio::main]
@@ -58,6 +85,12 @@ async fn main() {
         .allow_headers(vec!["content-type", "authorization"])
         .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"]);
 
+    let store: SharedUserStore = std::sync::Arc::new(
+        store::MongoUserStore::connect()
+            .await
+            .expect("Failed to connect to MongoDB"),
+    );
+
     // Routes
     let health = warp::path("health")
         .and(warp::get())
@@ -71,12 +104,14 @@ async fn main() {
         .and(warp::path("login"))
         .and(warp::post())
         .and(warp::body::json())
+        .and(with_store(store.clone()))
         .and_then(handle_login);
 
     let register = warp::path("auth")
         .and(warp::path("register"))
         .and(warp::post())
         .and(warp::body::json())
+        .and(with_store(store.clone()))
         .and_then(handle_register);
 
     let verify_token = warp::path("auth")
@@ -85,10 +120,63 @@ async fn main() {
         .and(warp::header::<String>("authorization"))
         .and_then(handle_verify_token);
 
+    let refresh = warp::path("auth")
+        .and(warp::path("refresh"))
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_store(store.clone()))
+        .and_then(handle_refresh);
+
+    let logout = warp::path("auth")
+        .and(warp::path("logout"))
+        .and(warp::post())
+        .and(warp::body::json())
+        .and(with_store(store.clone()))
+        .and_then(handle_logout);
+
+    let oidc_registry = oidc::load_registry();
+
+    let oidc_registry_for_start = oidc_registry.clone();
+    let oidc_start = warp::path!("auth" / "oidc" / String / "start")
+        .and(warp::get())
+        .and(warp::any().map(move || oidc_registry_for_start.clone()))
+        .and_then(oidc::handle_start);
+
+    let oidc_registry_for_callback = oidc_registry.clone();
+    let oidc_callback = warp::path!("auth" / "oidc" / String / "callback")
+        .and(warp::get())
+        .and(warp::query::<oidc::OidcCallbackQuery>())
+        .and(warp::any().map(move || oidc_registry_for_callback.clone()))
+        .and(with_store(store.clone()))
+        .and_then(oidc::handle_callback);
+
+    let me = warp::path!("auth" / "me")
+        .and(warp::get())
+        .and(guards::require_auth())
+        .map(|claims: Claims| {
+            warp::reply::json(&serde_json::json!({
+                "user_id": claims.user_id,
+                "email": claims.email,
+                "role": claims.role
+            }))
+        });
+
+    let admin_ping = warp::path!("auth" / "admin" / "ping")
+        .and(warp::get())
+        .and(guards::require_role("admin"))
+        .map(|_claims: Claims| warp::reply::json(&serde_json::json!({ "success": true })));
+
     let routes = health
         .or(login)
         .or(register)
         .or(verify_token)
+        .or(refresh)
+        .or(logout)
+        .or(oidc_start)
+        .or(oidc_callback)
+        .or(me)
+        .or(admin_ping)
+        .recover(errors::recover)
         .with(cors)
         .with(warp::log("crown_auth"));
 
@@ -101,69 +189,80 @@ async fn main() {
     warp::serve(routes).run(([0, 0, 0, 0], port)).await;
 }
 
-async fn handle_login(login_req: LoginRequest) -> Result<impl warp::Reply, warp::Rejection> {
-    // Simulate database lookup
-    // In production, this would connect to MongoDB
-    let user = simulate_user_lookup(&login_req.email).await;
-    
-    match user {
-        Some(user_data) => {
-            if verify(&login_req.password, &user_data.password_hash).unwrap_or(false) {
-                let token = create_jwt_token(&user_data)?;
-                let response = AuthResponse {
-                    success: true,
-                    message: "Login successful".to_string(),
-                    token: Some(token),
-                    user: Some(UserInfo {
-                        id: user_data.id,
-                        email: user_data.email,
-                        first_name: user_data.first_name,
-                        last_name: user_data.last_name,
-                        role: user_data.role,
-                    }),
-                };
-                Ok(warp::reply::json(&response))
-            } else {
-                let response = AuthResponse {
-                    success: false,
-                    message: "Invalid credentials".to_string(),
-                    token: None,
-                    user: None,
-                };
-                Ok(warp::reply::json(&response))
-            }
-        }
-        None => {
-            let response = AuthResponse {
-                success: false,
-                message: "User not found".to_string(),
-                token: None,
-                user: None,
-            };
-            Ok(warp::reply::json(&response))
-        }
+/// Injects the shared `UserStore` into a filter chain as a regular
+/// extracted value, so handlers take it as a parameter instead of reaching
+/// for a free function.
+pub(crate) fn with_store(
+    store: SharedUserStore,
+) -> impl Filter<Extract = (SharedUserStore,), Error = Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
+async fn handle_login(
+    login_req: LoginRequest,
+    store: SharedUserStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if login_req.email.is_empty() || login_req.password.is_empty() {
+        return Err(warp::reject::custom(AuthError::MissingCredentials));
+    }
+
+    let user_data = store
+        .find_by_email(&login_req.email)
+        .await
+        .map_err(to_rejection)?
+        .ok_or_else(|| warp::reject::custom(AuthError::UserNotFound))?;
+
+    if user_data.blocked {
+        return Err(warp::reject::custom(AuthError::BlockedUser));
     }
+
+    if !verify(&login_req.password, &user_data.password_hash).unwrap_or(false) {
+        return Err(warp::reject::custom(AuthError::InvalidCredentials));
+    }
+
+    let token = create_jwt_token(&user_data)?;
+    let refresh_token = issue_refresh_token(&store, &user_data.id, &user_data.email).await?;
+    let response = AuthResponse {
+        success: true,
+        message: "Login successful".to_string(),
+        token: Some(token),
+        refresh_token: Some(refresh_token),
+        user: Some(UserInfo {
+            id: user_data.id,
+            email: user_data.email,
+            first_name: user_data.first_name,
+            last_name: user_data.last_name,
+            role: user_data.role,
+        }),
+    };
+    Ok(warp::reply::json(&response))
 }
 
-async fn handle_register(register_req: RegisterRequest) -> Result<impl warp::Reply, warp::Rejection> {
+async fn handle_register(
+    register_req: RegisterRequest,
+    store: SharedUserStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
     let password_hash = hash(&register_req.password, DEFAULT_COST).unwrap();
-    let user_id = Uuid::new_v4().to_string();
-    
-    // Simulate user creation
+
     let user_data = UserData {
-        id: user_id,
+        id: Uuid::new_v4().to_string(),
         email: register_req.email,
         first_name: register_req.first_name,
         last_name: register_req.last_name,
         password_hash,
         role: "user".to_string(),
+        blocked: false,
     };
-    
+
+    store.insert(user_data.clone()).await.map_err(to_rejection)?;
+
     let token = create_jwt_token(&user_data)?;
+    let refresh_token = issue_refresh_token(&store, &user_data.id, &user_data.email).await?;
     let response = AuthResponse {
         success: true,
         message: "Registration successful".to_string(),
         token: Some(token),
+        refresh_token: Some(refresh_token),
         user: Some(UserInfo {
             id: user_data.id,
             email: user_data.email,
@@ -172,44 +271,83 @@ async fn handle_register(register_req: RegisterRequest) -> Result<impl warp::Rep
             role: user_data.role,
         }),
     };
-    
+
     Ok(warp::reply::json(&response))
 }
 
-async fn handle_verify_token(auth_header: String) -> Result<impl warp::Reply, warp::Rejection> {
-    if let Some(token) = auth_header.strip_prefix("Bearer ") {
-        match verify_jwt_token(token) {
-            Ok(claims) => {
-                let response = serde_json::json!({
-                    "valid": true,
-                    "user_id": claims.user_id,
-                    "email": claims.email,
-                    "role": claims.role,
-                    "expires_at": claims.exp
-                });
-                Ok(warp::reply::json(&response))
-            }
-            Err(_) => {
-                let response = serde_json::json!({
-                    "valid": false,
-                    "error": "Invalid or expired token"
-                });
-                Ok(warp::reply::json(&response))
-            }
+async fn handle_refresh(
+    refresh_req: RefreshRequest,
+    store: SharedUserStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let presented_hash = hash_refresh_token(&refresh_req.refresh_token);
+
+    // Rotation: the old record is consumed as soon as we look it up, valid or not.
+    let record = store.take_refresh_token(&presented_hash).await.map_err(to_rejection)?;
+
+    let record = match record {
+        Some(record) if record.expires_at > Utc::now().timestamp() => record,
+        _ => return Err(warp::reject::custom(AuthError::InvalidRefreshToken)),
+    };
+
+    match store.find_by_email(&record.email).await.map_err(to_rejection)? {
+        Some(user_data) if user_data.blocked => Err(warp::reject::custom(AuthError::BlockedUser)),
+        Some(user_data) => {
+            let token = create_jwt_token(&user_data)?;
+            let refresh_token = issue_refresh_token(&store, &user_data.id, &user_data.email).await?;
+            let response = AuthResponse {
+                success: true,
+                message: "Token refreshed".to_string(),
+                token: Some(token),
+                refresh_token: Some(refresh_token),
+                user: Some(UserInfo {
+                    id: user_data.id,
+                    email: user_data.email,
+                    first_name: user_data.first_name,
+                    last_name: user_data.last_name,
+                    role: user_data.role,
+                }),
+            };
+            Ok(warp::reply::json(&response))
         }
-    } else {
-        let response = serde_json::json!({
-            "valid": false,
-            "error": "Invalid authorization header format"
-        });
-        Ok(warp::reply::json(&response))
+        None => Err(warp::reject::custom(AuthError::UserNotFound)),
     }
 }
 
-fn create_jwt_token(user: &UserData) -> Result<String, warp::Rejection> {
+async fn handle_logout(
+    logout_req: LogoutRequest,
+    store: SharedUserStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let presented_hash = hash_refresh_token(&logout_req.refresh_token);
+    store.delete_refresh_token(&presented_hash).await.map_err(to_rejection)?;
+
+    let response = serde_json::json!({
+        "success": true,
+        "message": "Logged out"
+    });
+    Ok(warp::reply::json(&response))
+}
+
+async fn handle_verify_token(auth_header: String) -> Result<impl warp::Reply, warp::Rejection> {
+    let token = auth_header
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| warp::reject::custom(AuthError::InvalidToken))?;
+
+    let claims = verify_jwt_token(token).map_err(|e| warp::reject::custom(classify_token_error(&e)))?;
+
+    let response = serde_json::json!({
+        "valid": true,
+        "user_id": claims.user_id,
+        "email": claims.email,
+        "role": claims.role,
+        "expires_at": claims.exp
+    });
+    Ok(warp::reply::json(&response))
+}
+
+pub(crate) fn create_jwt_token(user: &UserData) -> Result<String, warp::Rejection> {
     let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "your-secret-key".to_string());
     let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
+        .checked_add_signed(Duration::minutes(ACCESS_TOKEN_TTL_MINUTES))
         .expect("Invalid timestamp")
         .timestamp() as usize;
 
@@ -230,10 +368,10 @@ fn create_jwt_token(user: &UserData) -> Result<String, warp::Rejection> {
     .map_err(|_| warp::reject::custom(AuthError::TokenCreationError))
 }
 
-fn verify_jwt_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+pub(crate) fn verify_jwt_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
     let secret = env::var("JWT_SECRET").unwrap_or_else(|_| "your-secret-key".to_string());
     let validation = Validation::new(Algorithm::HS256);
-    
+
     decode::<Claims>(
         token,
         &DecodingKey::from_secret(secret.as_ref()),
@@ -242,50 +380,66 @@ fn verify_jwt_token(token: &str) -> Result<Claims, jsonwebtoken::errors::Error>
     .map(|data| data.claims)
 }
 
-#[derive(Debug)]
-struct UserData {
-    id: String,
-    email: String,
-    first_name: String,
-    last_name: String,
-    password_hash: String,
-    role: String,
+/// Tells an expired-but-otherwise-valid token apart from a malformed or
+/// tampered one, so front-ends can trigger a silent refresh only on expiry.
+pub(crate) fn classify_token_error(err: &jsonwebtoken::errors::Error) -> AuthError {
+    match err.kind() {
+        jsonwebtoken::errors::ErrorKind::ExpiredSignature => AuthError::ExpiredToken,
+        _ => AuthError::InvalidToken,
+    }
 }
 
-async fn simulate_user_lookup(email: &str) -> Option<UserData> {
-    // Simulate database lookup
-    // In production, connect to MongoDB here
-    if email == "admin@crown.com" {
-        Some(UserData {
-            id: "admin-id".to_string(),
-            email: email.to_string(),
-            first_name: "Admin".to_string(),
-            last_name: "User".to_string(),
-            password_hash: hash("admin123", DEFAULT_COST).unwrap(),
-            role: "admin".to_string(),
-        })
-    } else {
-        None
+fn to_rejection(err: store::StoreError) -> warp::Rejection {
+    match err {
+        store::StoreError::EmailTaken => warp::reject::custom(AuthError::EmailTaken),
+        store::StoreError::Internal(message) => warp::reject::custom(AuthError::Internal(message)),
     }
 }
 
-#[derive(Debug)]
-struct AuthError {
-    message: String,
+fn hash_refresh_token(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    format!("{:x}", hasher.finalize())
 }
 
-impl AuthError {
-    fn token_creation_error() -> Self {
-        AuthError {
-            message: "Failed to create token".to_string(),
-        }
-    }
-}
+/// Generates a fresh opaque refresh token, persists its hash alongside the
+/// owning user and an expiry, and returns the raw token to hand to the client.
+pub(crate) async fn issue_refresh_token(
+    store: &SharedUserStore,
+    user_id: &str,
+    email: &str,
+) -> Result<String, warp::Rejection> {
+    let mut raw = [0u8; 64];
+    rand::thread_rng().fill_bytes(&mut raw);
+    let token = general_purpose::STANDARD.encode(raw);
 
-impl warp::reject::Reject for AuthError {}
+    let expires_at = Utc::now()
+        .checked_add_signed(Duration::days(REFRESH_TOKEN_TTL_DAYS))
+        .expect("Invalid timestamp")
+        .timestamp();
 
-impl AuthError {
-    const TokenCreationError: AuthError = AuthError {
-        message: String::new(),
-    };
+    store
+        .save_refresh_token(
+            &hash_refresh_token(&token),
+            store::RefreshRecord {
+                user_id: user_id.to_string(),
+                email: email.to_string(),
+                expires_at,
+            },
+        )
+        .await
+        .map_err(to_rejection)?;
+
+    Ok(token)
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct UserData {
+    pub(crate) id: String,
+    pub(crate) email: String,
+    pub(crate) first_name: String,
+    pub(crate) last_name: String,
+    pub(crate) password_hash: String,
+    pub(crate) role: String,
+    pub(crate) blocked: bool,
 }
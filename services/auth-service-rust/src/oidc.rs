@@ -0,0 +1,320 @@
+// Sign-in with an external identity provider (Google, GitHub, or any other
+// OIDC/OAuth2 issuer), driven entirely from env config so providers can be
+// added without a code change. Crown's own JWT is still the session token;
+// the provider is only ever used to establish who the user is.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{Duration, Utc};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use warp::http::StatusCode;
+use warp::reply::Reply;
+
+use crate::store::{SharedUserStore, UserStore};
+use crate::{create_jwt_token, issue_refresh_token, AuthResponse, UserData, UserInfo};
+
+const STATE_TTL_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone)]
+struct OidcProviderConfig {
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    authorize_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    scopes: Vec<String>,
+}
+
+pub(crate) type OidcRegistry = Arc<HashMap<String, OidcProviderConfig>>;
+
+/// Builds the provider registry from env. Set `OIDC_PROVIDERS` to a
+/// comma-separated list of provider keys (e.g. `google,github`), then for
+/// each key, upper-cased as `P`, provide `OIDC_{P}_CLIENT_ID`, `OIDC_{P}_CLIENT_SECRET`,
+/// `OIDC_{P}_REDIRECT_URI`, `OIDC_{P}_AUTHORIZE_URL`, `OIDC_{P}_TOKEN_URL`
+/// and `OIDC_{P}_USERINFO_URL`. `OIDC_{P}_SCOPES` defaults to
+/// "openid email profile". Providers missing a required var are skipped.
+pub(crate) fn load_registry() -> OidcRegistry {
+    let mut providers = HashMap::new();
+
+    let configured = env::var("OIDC_PROVIDERS").unwrap_or_default();
+    for name in configured.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let prefix = format!("OIDC_{}", name.to_uppercase());
+        let var = |suffix: &str| env::var(format!("{}_{}", prefix, suffix));
+
+        let (Ok(client_id), Ok(client_secret), Ok(redirect_uri)) =
+            (var("CLIENT_ID"), var("CLIENT_SECRET"), var("REDIRECT_URI"))
+        else {
+            continue;
+        };
+        let (Ok(authorize_endpoint), Ok(token_endpoint), Ok(userinfo_endpoint)) =
+            (var("AUTHORIZE_URL"), var("TOKEN_URL"), var("USERINFO_URL"))
+        else {
+            continue;
+        };
+
+        let scopes = var("SCOPES")
+            .unwrap_or_else(|_| "openid email profile".to_string())
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+
+        providers.insert(
+            name.to_string(),
+            OidcProviderConfig {
+                client_id,
+                client_secret,
+                redirect_uri,
+                authorize_endpoint,
+                token_endpoint,
+                userinfo_endpoint,
+                scopes,
+            },
+        );
+    }
+
+    Arc::new(providers)
+}
+
+/// A `state` we handed out, waiting to come back on the callback.
+struct PendingAuthorization {
+    provider: String,
+    code_verifier: String,
+    expires_at: i64,
+}
+
+// Process-global, in-memory on purpose: the start/callback handshake only
+// needs `state` to survive the round trip to the identity provider and back.
+// This does mean the handshake breaks if the callback lands on a different
+// instance than the one that issued `state` (no sticky routing / shared
+// cache), which is acceptable for now but worth revisiting if this service
+// is ever scaled horizontally.
+fn pending_store() -> &'static Mutex<HashMap<String, PendingAuthorization>> {
+    static STORE: OnceLock<Mutex<HashMap<String, PendingAuthorization>>> = OnceLock::new();
+    STORE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn random_url_safe(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn generate_pkce_pair() -> (String, String) {
+    let verifier = random_url_safe(32);
+    let mut hasher = Sha256::new();
+    hasher.update(verifier.as_bytes());
+    let challenge = general_purpose::URL_SAFE_NO_PAD.encode(hasher.finalize());
+    (verifier, challenge)
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct OidcCallbackQuery {
+    code: String,
+    state: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserInfoResponse {
+    email: String,
+    #[serde(default)]
+    given_name: String,
+    #[serde(default)]
+    family_name: String,
+    #[serde(default)]
+    name: String,
+}
+
+/// Mirrors the `{ status, message }` envelope `errors::recover` uses for
+/// every other failure response, so OIDC failures don't carry a second,
+/// inconsistent error shape.
+fn error_response(status: StatusCode, message: &str) -> warp::reply::Response {
+    warp::reply::with_status(
+        warp::reply::json(&serde_json::json!({
+            "status": status.as_u16(),
+            "message": message
+        })),
+        status,
+    )
+    .into_response()
+}
+
+pub(crate) async fn handle_start(
+    provider: String,
+    registry: OidcRegistry,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(config) = registry.get(&provider) else {
+        return Ok(error_response(StatusCode::NOT_FOUND, "Unknown identity provider"));
+    };
+
+    let state = random_url_safe(32);
+    let (code_verifier, code_challenge) = generate_pkce_pair();
+    let expires_at = Utc::now()
+        .checked_add_signed(Duration::minutes(STATE_TTL_MINUTES))
+        .expect("Invalid timestamp")
+        .timestamp();
+
+    pending_store().lock().unwrap().insert(
+        state.clone(),
+        PendingAuthorization {
+            provider: provider.clone(),
+            code_verifier,
+            expires_at,
+        },
+    );
+
+    let authorize_url = format!(
+        "{}?response_type=code&client_id={}&redirect_uri={}&scope={}&state={}&code_challenge={}&code_challenge_method=S256",
+        config.authorize_endpoint,
+        urlencoding::encode(&config.client_id),
+        urlencoding::encode(&config.redirect_uri),
+        urlencoding::encode(&config.scopes.join(" ")),
+        urlencoding::encode(&state),
+        urlencoding::encode(&code_challenge),
+    );
+
+    let uri = match authorize_url.parse::<warp::http::Uri>() {
+        Ok(uri) => uri,
+        Err(_) => return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Invalid authorize URL")),
+    };
+
+    Ok(warp::redirect::found(uri).into_response())
+}
+
+pub(crate) async fn handle_callback(
+    provider: String,
+    query: OidcCallbackQuery,
+    registry: OidcRegistry,
+    store: SharedUserStore,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let Some(config) = registry.get(&provider) else {
+        return Ok(error_response(StatusCode::NOT_FOUND, "Unknown identity provider"));
+    };
+
+    let pending = pending_store().lock().unwrap().remove(&query.state);
+    let pending = match pending {
+        Some(pending) if pending.provider == provider && pending.expires_at > Utc::now().timestamp() => pending,
+        _ => return Ok(error_response(StatusCode::BAD_REQUEST, "Invalid or expired state")),
+    };
+
+    let client = reqwest::Client::new();
+
+    let token_response = client
+        .post(&config.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", query.code.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("client_secret", config.client_secret.as_str()),
+            ("code_verifier", pending.code_verifier.as_str()),
+        ])
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status());
+
+    let token_data: TokenResponse = match token_response {
+        Ok(resp) => match resp.json().await {
+            Ok(data) => data,
+            Err(_) => return Ok(error_response(StatusCode::BAD_GATEWAY, "Malformed token response")),
+        },
+        Err(_) => return Ok(error_response(StatusCode::BAD_GATEWAY, "Failed to exchange authorization code")),
+    };
+
+    let userinfo_response = client
+        .get(&config.userinfo_endpoint)
+        .bearer_auth(&token_data.access_token)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status());
+
+    let userinfo: UserInfoResponse = match userinfo_response {
+        Ok(resp) => match resp.json().await {
+            Ok(data) => data,
+            Err(_) => return Ok(error_response(StatusCode::BAD_GATEWAY, "Malformed userinfo response")),
+        },
+        Err(_) => return Ok(error_response(StatusCode::BAD_GATEWAY, "Failed to fetch user info")),
+    };
+
+    let (first_name, last_name) = split_name(&userinfo);
+
+    let existing = match store.find_by_email(&userinfo.email).await {
+        Ok(existing) => existing,
+        Err(_) => return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to look up user")),
+    };
+
+    let user_data = match existing {
+        Some(existing) => existing,
+        None => {
+            let provisioned = UserData {
+                id: uuid::Uuid::new_v4().to_string(),
+                email: userinfo.email,
+                first_name,
+                last_name,
+                password_hash: String::new(),
+                role: "user".to_string(),
+                blocked: false,
+            };
+            if let Err(err) = store.insert(provisioned.clone()).await {
+                return Ok(match err {
+                    crate::store::StoreError::EmailTaken => {
+                        error_response(StatusCode::CONFLICT, "An account with this email already exists")
+                    }
+                    crate::store::StoreError::Internal(_) => {
+                        error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to provision user")
+                    }
+                });
+            }
+            provisioned
+        }
+    };
+
+    if user_data.blocked {
+        return Ok(error_response(StatusCode::FORBIDDEN, "This account has been blocked"));
+    }
+
+    let token = match create_jwt_token(&user_data) {
+        Ok(token) => token,
+        Err(_) => return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")),
+    };
+    let refresh_token = match issue_refresh_token(&store, &user_data.id, &user_data.email).await {
+        Ok(token) => token,
+        Err(_) => return Ok(error_response(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create session")),
+    };
+
+    let response = AuthResponse {
+        success: true,
+        message: "Login successful".to_string(),
+        token: Some(token),
+        refresh_token: Some(refresh_token),
+        user: Some(UserInfo {
+            id: user_data.id,
+            email: user_data.email,
+            first_name: user_data.first_name,
+            last_name: user_data.last_name,
+            role: user_data.role,
+        }),
+    };
+
+    Ok(warp::reply::json(&response).into_response())
+}
+
+fn split_name(info: &UserInfoResponse) -> (String, String) {
+    if !info.given_name.is_empty() || !info.family_name.is_empty() {
+        return (info.given_name.clone(), info.family_name.clone());
+    }
+    let mut parts = info.name.splitn(2, ' ');
+    let first = parts.next().unwrap_or_default().to_string();
+    let last = parts.next().unwrap_or_default().to_string();
+    (first, last)
+}
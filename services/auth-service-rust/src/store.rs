@@ -0,0 +1,192 @@
+// Pluggable persistence for users and their refresh tokens, behind a trait
+// so handlers depend on `UserStore` rather than a MongoDB client directly.
+// Wired into warp filters via `warp::any().map(move || store.clone())`
+// instead of a free function, the same way the Database abstraction is
+// threaded through in the other backends.
+
+use std::env;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use mongodb::bson::doc;
+use mongodb::error::{ErrorKind, WriteFailure};
+use mongodb::options::{ClientOptions, IndexOptions};
+use mongodb::{Client, Collection, IndexModel};
+use serde::{Deserialize, Serialize};
+
+use crate::UserData;
+
+const DUPLICATE_KEY_ERROR_CODE: i32 = 11000;
+
+#[derive(Debug)]
+pub(crate) enum StoreError {
+    EmailTaken,
+    Internal(String),
+}
+
+/// A live refresh token record. Stored keyed by the SHA-256 hash of the
+/// opaque token so the raw token is never held at rest.
+#[derive(Debug, Clone)]
+pub(crate) struct RefreshRecord {
+    pub(crate) user_id: String,
+    pub(crate) email: String,
+    pub(crate) expires_at: i64,
+}
+
+#[async_trait]
+pub(crate) trait UserStore: Send + Sync {
+    async fn find_by_email(&self, email: &str) -> Result<Option<UserData>, StoreError>;
+    async fn insert(&self, user: UserData) -> Result<(), StoreError>;
+    #[allow(dead_code)]
+    async fn set_blocked(&self, user_id: &str, blocked: bool) -> Result<(), StoreError>;
+
+    async fn save_refresh_token(&self, token_hash: &str, record: RefreshRecord) -> Result<(), StoreError>;
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshRecord>, StoreError>;
+    async fn delete_refresh_token(&self, token_hash: &str) -> Result<(), StoreError>;
+}
+
+pub(crate) type SharedUserStore = Arc<dyn UserStore>;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UserDocument {
+    #[serde(rename = "_id")]
+    id: String,
+    email: String,
+    first_name: String,
+    last_name: String,
+    password_hash: String,
+    role: String,
+    blocked: bool,
+}
+
+impl From<UserData> for UserDocument {
+    fn from(user: UserData) -> Self {
+        UserDocument {
+            id: user.id,
+            email: user.email,
+            first_name: user.first_name,
+            last_name: user.last_name,
+            password_hash: user.password_hash,
+            role: user.role,
+            blocked: user.blocked,
+        }
+    }
+}
+
+impl From<UserDocument> for UserData {
+    fn from(doc: UserDocument) -> Self {
+        UserData {
+            id: doc.id,
+            email: doc.email,
+            first_name: doc.first_name,
+            last_name: doc.last_name,
+            password_hash: doc.password_hash,
+            role: doc.role,
+            blocked: doc.blocked,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RefreshTokenDocument {
+    #[serde(rename = "_id")]
+    token_hash: String,
+    user_id: String,
+    email: String,
+    expires_at: i64,
+}
+
+pub(crate) struct MongoUserStore {
+    users: Collection<UserDocument>,
+    refresh_tokens: Collection<RefreshTokenDocument>,
+}
+
+impl MongoUserStore {
+    pub(crate) async fn connect() -> mongodb::error::Result<Self> {
+        let uri = env::var("MONGODB_URI").unwrap_or_else(|_| "mongodb://localhost:27017".to_string());
+        let options = ClientOptions::parse(&uri).await?;
+        let client = Client::with_options(options)?;
+        let database = client.database("crown_auth");
+
+        let users: Collection<UserDocument> = database.collection("users");
+        let refresh_tokens: Collection<RefreshTokenDocument> = database.collection("refresh_tokens");
+
+        let email_index = IndexModel::builder()
+            .keys(doc! { "email": 1 })
+            .options(IndexOptions::builder().unique(true).build())
+            .build();
+        users.create_index(email_index, None).await?;
+
+        Ok(Self { users, refresh_tokens })
+    }
+}
+
+#[async_trait]
+impl UserStore for MongoUserStore {
+    async fn find_by_email(&self, email: &str) -> Result<Option<UserData>, StoreError> {
+        self.users
+            .find_one(doc! { "email": email }, None)
+            .await
+            .map(|doc| doc.map(UserData::from))
+            .map_err(|err| StoreError::Internal(err.to_string()))
+    }
+
+    async fn insert(&self, user: UserData) -> Result<(), StoreError> {
+        match self.users.insert_one(UserDocument::from(user), None).await {
+            Ok(_) => Ok(()),
+            Err(err) if is_duplicate_key_error(&err) => Err(StoreError::EmailTaken),
+            Err(err) => Err(StoreError::Internal(err.to_string())),
+        }
+    }
+
+    async fn set_blocked(&self, user_id: &str, blocked: bool) -> Result<(), StoreError> {
+        self.users
+            .update_one(doc! { "_id": user_id }, doc! { "$set": { "blocked": blocked } }, None)
+            .await
+            .map(|_| ())
+            .map_err(|err| StoreError::Internal(err.to_string()))
+    }
+
+    async fn save_refresh_token(&self, token_hash: &str, record: RefreshRecord) -> Result<(), StoreError> {
+        let document = RefreshTokenDocument {
+            token_hash: token_hash.to_string(),
+            user_id: record.user_id,
+            email: record.email,
+            expires_at: record.expires_at,
+        };
+        self.refresh_tokens
+            .insert_one(document, None)
+            .await
+            .map(|_| ())
+            .map_err(|err| StoreError::Internal(err.to_string()))
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<RefreshRecord>, StoreError> {
+        self.refresh_tokens
+            .find_one_and_delete(doc! { "_id": token_hash }, None)
+            .await
+            .map(|doc| {
+                doc.map(|document| RefreshRecord {
+                    user_id: document.user_id,
+                    email: document.email,
+                    expires_at: document.expires_at,
+                })
+            })
+            .map_err(|err| StoreError::Internal(err.to_string()))
+    }
+
+    async fn delete_refresh_token(&self, token_hash: &str) -> Result<(), StoreError> {
+        self.refresh_tokens
+            .delete_one(doc! { "_id": token_hash }, None)
+            .await
+            .map(|_| ())
+            .map_err(|err| StoreError::Internal(err.to_string()))
+    }
+}
+
+fn is_duplicate_key_error(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY_ERROR_CODE
+    )
+}